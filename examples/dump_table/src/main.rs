@@ -1,4 +1,6 @@
 use anyhow;
+use cpp_demangle::Symbol as CppSymbol;
+use flate2::read::GzDecoder;
 use futures;
 use memmap::MmapOptions;
 use profiler_get_symbols::{
@@ -7,6 +9,7 @@ use profiler_get_symbols::{
 };
 use std::fs::File;
 use std::future::Future;
+use std::io::Read;
 use std::path::{Path, PathBuf};
 use std::pin::Pin;
 use structopt::StructOpt;
@@ -32,6 +35,66 @@ struct Opt {
     /// When specified, print the entire symbol table.
     #[structopt(short, long)]
     full: bool,
+
+    /// Base URL of a Microsoft-style symbol server to fall back to when the
+    /// binary or PDB isn't found in the symbol directory, e.g.
+    /// https://msdl.microsoft.com/download/symbols. Can be passed multiple
+    /// times; servers are tried in the order given.
+    #[structopt(long = "symbol-server")]
+    symbol_servers: Vec<String>,
+
+    /// Directory used to cache files downloaded from a symbol server.
+    #[structopt(long, default_value = "symbol_cache")]
+    symbol_cache_dir: PathBuf,
+
+    /// Resolve each given hex address (e.g. 0x1234abcd) to `name+offset`
+    /// instead of dumping the whole table. Pass `-` to read addresses
+    /// one per line from stdin instead.
+    #[structopt(long)]
+    addresses: Vec<String>,
+
+    /// Output format for the symbol table: `text` (default) or `breakpad`
+    /// (a Google Breakpad `.sym` file).
+    #[structopt(long, default_value = "text")]
+    format: OutputFormat,
+
+    /// Demangle C++ (Itanium and MSVC) and Rust symbol names before
+    /// printing them. Names that fail to demangle are printed as-is.
+    #[structopt(long)]
+    demangle: bool,
+
+    /// OS name to put in the `MODULE` line of `--format breakpad` output
+    /// (e.g. `windows`, `mac`, `Linux`). Auto-detected from the debug file's
+    /// extension when omitted; pass this explicitly when that guess is wrong.
+    #[structopt(long)]
+    breakpad_os: Option<String>,
+
+    /// CPU architecture to put in the `MODULE` line of `--format breakpad`
+    /// output (e.g. `x86_64`, `arm64`). Defaults to the host architecture,
+    /// which is only a guess when the debug file is for another platform.
+    #[structopt(long)]
+    breakpad_arch: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OutputFormat {
+    Text,
+    Breakpad,
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(OutputFormat::Text),
+            "breakpad" => Ok(OutputFormat::Breakpad),
+            other => Err(format!(
+                "unknown output format {:?} (expected `text` or `breakpad`)",
+                other
+            )),
+        }
+    }
 }
 
 fn main() -> anyhow::Result<()> {
@@ -40,7 +103,14 @@ fn main() -> anyhow::Result<()> {
         &opt.debug_name,
         opt.breakpad_id,
         opt.symbol_directory,
+        opt.symbol_servers,
+        opt.symbol_cache_dir,
         opt.full,
+        opt.addresses,
+        opt.format,
+        opt.demangle,
+        opt.breakpad_os,
+        opt.breakpad_arch,
     ))
 }
 
@@ -48,9 +118,39 @@ async fn dump_table(
     debug_name: &str,
     breakpad_id: Option<String>,
     symbol_directory: PathBuf,
+    symbol_servers: Vec<String>,
+    symbol_cache_dir: PathBuf,
     full: bool,
+    addresses: Vec<String>,
+    format: OutputFormat,
+    demangle: bool,
+    breakpad_os: Option<String>,
+    breakpad_arch: Option<String>,
 ) -> anyhow::Result<()> {
-    let table = get_table(debug_name, breakpad_id, symbol_directory).await?;
+    let (breakpad_id, table) = get_table(
+        debug_name,
+        breakpad_id,
+        symbol_directory,
+        symbol_servers,
+        symbol_cache_dir,
+    )
+    .await?;
+
+    if !addresses.is_empty() {
+        return resolve_addresses(&table, &addresses, demangle);
+    }
+
+    if format == OutputFormat::Breakpad {
+        return emit_breakpad(
+            debug_name,
+            &breakpad_id,
+            &table,
+            demangle,
+            breakpad_os.as_deref(),
+            breakpad_arch.as_deref(),
+        );
+    }
+
     println!("Found {} symbols.", table.addr.len());
     for (i, address) in table.addr.iter().enumerate() {
         if i >= 15 && !full {
@@ -65,40 +165,203 @@ async fn dump_table(
         let end_pos = table.index[i + 1];
         let symbol_bytes = &table.buffer[start_pos as usize..end_pos as usize];
         let symbol_string = std::str::from_utf8(symbol_bytes)?;
+        let symbol_string = if demangle {
+            demangle_name(symbol_string)
+        } else {
+            symbol_string.to_owned()
+        };
         println!("{:x} {}", address, symbol_string);
     }
     Ok(())
 }
 
+/// Demangles `name` if it looks like an MSVC, Itanium (C++) or Rust mangled
+/// symbol, falling back to the original string when demangling fails.
+///
+/// `_ZN...` is the standard Itanium prefix for any namespaced/class C++
+/// symbol, but it's also what Rust's legacy mangling scheme uses, and a
+/// legacy Rust mangling happens to be valid Itanium grammar too: feeding it
+/// to `cpp_demangle` "succeeds" but leaves the trailing hash disambiguator
+/// in the output instead of stripping it the way `rustc_demangle` does. So
+/// for anything starting with `_Z` we always try `rustc_demangle::try_demangle`
+/// first — it correctly rejects names that aren't actually Rust manglings —
+/// and only fall back to `cpp_demangle` when that fails.
+fn demangle_name(name: &str) -> String {
+    if name.starts_with('?') {
+        if let Ok(demangled) = msvc_demangler::demangle(name, msvc_demangler::DemangleFlags::llvm())
+        {
+            return demangled;
+        }
+    } else if name.starts_with("_R") || name.starts_with("_Z") {
+        if let Ok(demangled) = rustc_demangle::try_demangle(name) {
+            return format!("{:#}", demangled);
+        }
+        if name.starts_with("_Z") {
+            if let Ok(symbol) = CppSymbol::new(name) {
+                if let Ok(demangled) = symbol.demangle(&Default::default()) {
+                    return demangled;
+                }
+            }
+        }
+    }
+    name.to_owned()
+}
+
+/// Resolves each address in `addresses` to `name+offset` and prints it.
+/// A literal `-` in `addresses` is expanded to one address per line read
+/// from stdin.
+fn resolve_addresses(
+    table: &CompactSymbolTable,
+    addresses: &[String],
+    demangle: bool,
+) -> anyhow::Result<()> {
+    for raw in addresses {
+        if raw == "-" {
+            for line in std::io::stdin().lines() {
+                let line = line?;
+                let line = line.trim();
+                if !line.is_empty() {
+                    print_resolved_address(table, line, demangle)?;
+                }
+            }
+        } else {
+            print_resolved_address(table, raw, demangle)?;
+        }
+    }
+    Ok(())
+}
+
+fn print_resolved_address(
+    table: &CompactSymbolTable,
+    raw: &str,
+    demangle: bool,
+) -> anyhow::Result<()> {
+    let address = u64::from_str_radix(raw.trim_start_matches("0x"), 16)?;
+    match lookup_address(table, address) {
+        Some((name, offset)) => {
+            let name = if demangle { demangle_name(&name) } else { name };
+            println!("{:#x} {} +{:#x}", address, name, offset)
+        }
+        None => println!("{:#x} <unsymbolicated>", address),
+    }
+    Ok(())
+}
+
+/// Serializes `table` as a Google Breakpad text-format `.sym` file: a
+/// `MODULE` header line followed by one `PUBLIC` line per symbol, in the
+/// format expected by `breakpad-symbols`' `SimpleSymbolSupplier`.
+fn emit_breakpad(
+    debug_name: &str,
+    breakpad_id: &str,
+    table: &CompactSymbolTable,
+    demangle: bool,
+    os_override: Option<&str>,
+    arch_override: Option<&str>,
+) -> anyhow::Result<()> {
+    if breakpad_id == "<unspecified>" {
+        anyhow::bail!(
+            "--format breakpad requires a resolved breakpad ID; pass the breakpad ID argument explicitly"
+        );
+    }
+
+    println!(
+        "MODULE {} {} {} {}",
+        os_override
+            .map(str::to_owned)
+            .unwrap_or_else(|| breakpad_os(debug_name)),
+        arch_override
+            .map(str::to_owned)
+            .unwrap_or_else(|| breakpad_arch()),
+        breakpad_id,
+        debug_name
+    );
+    for (i, address) in table.addr.iter().enumerate() {
+        let start_pos = table.index[i];
+        let end_pos = table.index[i + 1];
+        let symbol_bytes = &table.buffer[start_pos as usize..end_pos as usize];
+        let symbol_string = std::str::from_utf8(symbol_bytes)?;
+        let symbol_string = if demangle {
+            demangle_name(symbol_string)
+        } else {
+            symbol_string.to_owned()
+        };
+        println!("PUBLIC {:x} 0 {}", address, symbol_string);
+    }
+    Ok(())
+}
+
+/// Guesses the Breakpad `os` field from the debug file's own extension,
+/// since that's the file actually being symbolicated and not necessarily
+/// the platform `dump-table` runs on. Pass `--breakpad-os` to override.
+fn breakpad_os(debug_name: &str) -> String {
+    let lower = debug_name.to_ascii_lowercase();
+    if lower.ends_with(".pdb") {
+        "windows".to_owned()
+    } else if lower.ends_with(".dsym") || lower.contains(".dsym/") {
+        "mac".to_owned()
+    } else {
+        "Linux".to_owned()
+    }
+}
+
+/// The debug file alone doesn't reveal its CPU architecture, so this falls
+/// back to the host architecture; pass `--breakpad-arch` to override it
+/// when symbolicating a file for another platform.
+fn breakpad_arch() -> String {
+    match std::env::consts::ARCH {
+        "x86_64" => "x86_64",
+        "aarch64" => "arm64",
+        other => other,
+    }
+    .to_owned()
+}
+
+/// Finds the symbol containing `address`, returning its name and the offset
+/// of `address` within it. `table.addr` is sorted ascending, so we binary
+/// search for the last entry that starts at or before `address`.
+fn lookup_address(table: &CompactSymbolTable, address: u64) -> Option<(String, u64)> {
+    if table.addr.is_empty() || address < table.addr[0] {
+        return None;
+    }
+    let i = table.addr.partition_point(|&addr| addr <= address) - 1;
+    let offset = address - table.addr[i];
+    let start_pos = table.index[i] as usize;
+    let end_pos = table.index[i + 1] as usize;
+    let name = std::str::from_utf8(&table.buffer[start_pos..end_pos]).ok()?;
+    Some((name.to_owned(), offset))
+}
+
 async fn get_table(
     debug_name: &str,
     breakpad_id: Option<String>,
     symbol_directory: PathBuf,
-) -> anyhow::Result<CompactSymbolTable> {
-    let helper = Helper { symbol_directory };
-    let table = get_symbols_retry_id(debug_name, breakpad_id, &helper).await?;
-    Ok(table)
+    symbol_servers: Vec<String>,
+    symbol_cache_dir: PathBuf,
+) -> anyhow::Result<(String, CompactSymbolTable)> {
+    let helper = Helper {
+        symbol_directory,
+        symbol_servers,
+        symbol_cache_dir,
+    };
+    get_symbols_retry_id(debug_name, breakpad_id, &helper).await
 }
 
 async fn get_symbols_retry_id(
     debug_name: &str,
     breakpad_id: Option<String>,
     helper: &Helper,
-) -> anyhow::Result<CompactSymbolTable> {
+) -> anyhow::Result<(String, CompactSymbolTable)> {
     let breakpad_id = match breakpad_id {
         Some(breakpad_id) => breakpad_id,
         None => {
             // No breakpad ID was specified. get_compact_symbol_table always wants one, so we call it twice:
             // First, with a bogus breakpad ID ("<unspecified>"), and then again with the breakpad ID that
             // it expected.
-            let result = profiler_get_symbols::get_compact_symbol_table(
-                debug_name,
-                "<unspecified>",
-                helper,
-            )
-            .await;
+            let result =
+                profiler_get_symbols::get_compact_symbol_table(debug_name, "<unspecified>", helper)
+                    .await;
             match result {
-                Ok(table) => return Ok(table),
+                Ok(table) => return Ok((String::from("<unspecified>"), table)),
                 Err(err) => match err {
                     GetSymbolsError::UnmatchedBreakpadId(expected, _) => {
                         println!("Using breakpadID: {}", expected);
@@ -126,10 +389,9 @@ async fn get_symbols_retry_id(
             }
         }
     };
-    Ok(
-        profiler_get_symbols::get_compact_symbol_table(debug_name, &breakpad_id, helper)
-            .await?,
-    )
+    let table =
+        profiler_get_symbols::get_compact_symbol_table(debug_name, &breakpad_id, helper).await?;
+    Ok((breakpad_id, table))
 }
 
 struct MmapFileContents(memmap::Mmap);
@@ -142,6 +404,117 @@ impl OwnedFileData for MmapFileContents {
 
 struct Helper {
     symbol_directory: PathBuf,
+    symbol_servers: Vec<String>,
+    symbol_cache_dir: PathBuf,
+}
+
+impl Helper {
+    /// Downloads `debug_name`/`breakpad_id` from `server_base` (following the
+    /// Microsoft symbol server layout) into the symbol cache, unless it's
+    /// already cached, and returns the path to the cached file.
+    fn fetch_from_symbol_server(
+        server_base: &str,
+        debug_name: &str,
+        breakpad_id: &str,
+        symbol_cache_dir: &Path,
+    ) -> FileAndPathHelperResult<PathBuf> {
+        let cached_path = symbol_cache_dir
+            .join(debug_name)
+            .join(breakpad_id)
+            .join(debug_name);
+        if cached_path.exists() {
+            return Ok(cached_path);
+        }
+
+        let url = format!(
+            "{}/{}/{}/{}",
+            server_base.trim_end_matches('/'),
+            debug_name,
+            breakpad_id,
+            debug_name
+        );
+        println!("Downloading {}", url);
+        let response = ureq::get(&url).call()?;
+
+        let mut bytes = Vec::new();
+        if response.header("Content-Encoding") == Some("gzip") {
+            GzDecoder::new(response.into_reader()).read_to_end(&mut bytes)?;
+        } else {
+            response.into_reader().read_to_end(&mut bytes)?;
+        }
+
+        if let Some(parent) = cached_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(&cached_path, &bytes)?;
+        Ok(cached_path)
+    }
+}
+
+/// Debug-companion file extensions we consider a match when they share the
+/// queried debug file's basename but not its extension (e.g. `foo.exe` next
+/// to `foo.pdb`). Note that a real macOS dSYM is a *directory* named
+/// `Foo.dSYM` with an extensionless DWARF file nested inside it, so the
+/// `"dsym"` entry here only matches a literal flat file ending in `.dsym`;
+/// an actual dSYM bundle is only ever picked up incidentally, via the
+/// `is_same_name` exact-match branch below.
+const COMPANION_EXTENSIONS: &[&str] = &["pdb", "dsym", "debug"];
+
+/// Walks `symbol_directory` looking for every plausible location of
+/// `debug_name`: the flat `symbol_directory/<debug_name>` layout, the
+/// symbol-store layout keyed by breakpad ID
+/// (`symbol_directory/<debug_name>/<breakpad_id>/<debug_name>`), and any
+/// same-basename match or debug-companion file (see `COMPANION_EXTENSIONS`)
+/// nested anywhere below it.
+fn collect_local_candidates(
+    symbol_directory: &Path,
+    debug_name: &str,
+    breakpad_id: &str,
+) -> Vec<PathBuf> {
+    let mut candidates = vec![
+        symbol_directory.join(debug_name),
+        symbol_directory
+            .join(debug_name)
+            .join(breakpad_id)
+            .join(debug_name),
+    ];
+
+    let debug_stem = Path::new(debug_name).file_stem();
+    walk_dir(symbol_directory, &mut |path| {
+        let is_same_name = path.file_name() == Some(std::ffi::OsStr::new(debug_name));
+        let is_companion = debug_stem.is_some()
+            && path.file_stem() == debug_stem
+            && path
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .is_some_and(|ext| {
+                    COMPANION_EXTENSIONS
+                        .iter()
+                        .any(|c| ext.eq_ignore_ascii_case(c))
+                });
+        if (is_same_name || is_companion) && !candidates.contains(&path.to_path_buf()) {
+            candidates.push(path.to_path_buf());
+        }
+    });
+
+    candidates
+}
+
+/// Recursively visits every file below `dir`, silently skipping entries
+/// that can't be read (e.g. due to permissions).
+fn walk_dir(dir: &Path, visit: &mut impl FnMut(&Path)) {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            walk_dir(&path, visit);
+        } else {
+            visit(&path);
+        }
+    }
 }
 
 impl FileAndPathHelper for Helper {
@@ -150,14 +523,46 @@ impl FileAndPathHelper for Helper {
     fn get_candidate_paths_for_binary_or_pdb(
         &self,
         debug_name: &str,
-        _breakpad_id: &str,
+        breakpad_id: &str,
     ) -> Pin<Box<dyn Future<Output = FileAndPathHelperResult<Vec<PathBuf>>>>> {
-        async fn to_future(
-            res: FileAndPathHelperResult<Vec<PathBuf>>,
+        async fn candidate_paths(
+            symbol_directory: PathBuf,
+            symbol_servers: Vec<String>,
+            symbol_cache_dir: PathBuf,
+            debug_name: String,
+            breakpad_id: String,
         ) -> FileAndPathHelperResult<Vec<PathBuf>> {
-            res
+            let mut paths = collect_local_candidates(&symbol_directory, &debug_name, &breakpad_id);
+
+            // Only reach out to the configured symbol servers once the local
+            // symbol directory has nothing to offer; otherwise every lookup
+            // that's already satisfied locally would still pay for a network
+            // round-trip per configured server.
+            if !paths.iter().any(|path| path.exists()) {
+                for server_base in &symbol_servers {
+                    match Helper::fetch_from_symbol_server(
+                        server_base,
+                        &debug_name,
+                        &breakpad_id,
+                        &symbol_cache_dir,
+                    ) {
+                        Ok(path) => paths.push(path),
+                        Err(err) => {
+                            println!("Could not fetch from {}: {}", server_base, err);
+                        }
+                    }
+                }
+            }
+
+            Ok(paths)
         }
-        Box::pin(to_future(Ok(vec![self.symbol_directory.join(debug_name)])))
+        Box::pin(candidate_paths(
+            self.symbol_directory.clone(),
+            self.symbol_servers.clone(),
+            self.symbol_cache_dir.clone(),
+            debug_name.to_owned(),
+            breakpad_id.to_owned(),
+        ))
     }
 
     fn read_file(
@@ -177,6 +582,7 @@ impl FileAndPathHelper for Helper {
 #[cfg(test)]
 mod test {
 
+    use crate::CompactSymbolTable;
     use std::path::PathBuf;
 
     fn fixtures_dir() -> PathBuf {
@@ -184,15 +590,107 @@ mod test {
         this_dir.join("..").join("..").join("fixtures")
     }
 
+    fn test_table() -> CompactSymbolTable {
+        // Three symbols: "foo" @ 0x1000, "bar" @ 0x2000, "baz" @ 0x3000.
+        CompactSymbolTable {
+            addr: vec![0x1000, 0x2000, 0x3000],
+            index: vec![0, 3, 6, 9],
+            buffer: b"foobarbaz".to_vec(),
+        }
+    }
+
+    #[test]
+    fn lookup_address_exact_match() {
+        let table = test_table();
+        let (name, offset) = crate::lookup_address(&table, 0x2000).unwrap();
+        assert_eq!(name, "bar");
+        assert_eq!(offset, 0);
+    }
+
+    #[test]
+    fn lookup_address_with_offset() {
+        let table = test_table();
+        let (name, offset) = crate::lookup_address(&table, 0x2010).unwrap();
+        assert_eq!(name, "bar");
+        assert_eq!(offset, 0x10);
+    }
+
+    #[test]
+    fn lookup_address_last_symbol() {
+        let table = test_table();
+        let (name, offset) = crate::lookup_address(&table, 0x3fff).unwrap();
+        assert_eq!(name, "baz");
+        assert_eq!(offset, 0xfff);
+    }
+
+    #[test]
+    fn lookup_address_below_first_symbol_is_unsymbolicated() {
+        let table = test_table();
+        assert!(crate::lookup_address(&table, 0x500).is_none());
+    }
+
+    #[test]
+    fn lookup_address_empty_table_is_unsymbolicated() {
+        let table = CompactSymbolTable {
+            addr: vec![],
+            index: vec![0],
+            buffer: vec![],
+        };
+        assert!(crate::lookup_address(&table, 0x1000).is_none());
+    }
+
+    #[test]
+    fn demangle_name_itanium_namespaced_symbol() {
+        // A namespaced/class C++ symbol also starts with the ambiguous
+        // `_ZN` prefix that legacy Rust mangling uses; make sure it's
+        // demangled as C++ rather than left untouched.
+        assert_eq!(crate::demangle_name("_ZN3foo3barEv"), "foo::bar()");
+    }
+
+    #[test]
+    fn demangle_name_itanium_free_function() {
+        assert_eq!(crate::demangle_name("_Z3barv"), "bar()");
+    }
+
+    #[test]
+    fn demangle_name_legacy_rust_mangling_strips_hash() {
+        // Also `_ZN`-prefixed and valid Itanium grammar, but this is a real
+        // legacy Rust mangling: it must go through rustc_demangle (which
+        // strips the trailing hash) rather than cpp_demangle (which would
+        // leave `::h3d0916a8fb43c6a7` in the output).
+        assert_eq!(
+            crate::demangle_name("_ZN4core3fmt5Write9write_fmt17h3d0916a8fb43c6a7E"),
+            "core::fmt::Write::write_fmt"
+        );
+    }
+
+    #[test]
+    fn demangle_name_unrecognized_prefix_is_unchanged() {
+        assert_eq!(
+            crate::demangle_name(
+                "sandbox::ProcessMitigationsWin32KDispatcher::EnumDisplayMonitors"
+            ),
+            "sandbox::ProcessMitigationsWin32KDispatcher::EnumDisplayMonitors"
+        );
+    }
+
+    #[test]
+    fn demangle_name_invalid_msvc_name_falls_back_to_original() {
+        let name = "?not_a_real_msvc_mangled_name";
+        assert_eq!(crate::demangle_name(name), name);
+    }
+
     #[test]
     fn successful_pdb() {
         let result = futures::executor::block_on(crate::get_table(
             "firefox.pdb",
             Some(String::from("AA152DEB2D9B76084C4C44205044422E2")),
             fixtures_dir().join("win64-ci"),
+            vec![],
+            std::env::temp_dir().join("dump_table_test_cache"),
         ));
         assert!(result.is_ok());
-        let result = result.unwrap();
+        let (_breakpad_id, result) = result.unwrap();
         assert_eq!(result.addr.len(), 1286);
         assert_eq!(result.addr[776], 0x31fc0);
         assert_eq!(
@@ -208,9 +706,11 @@ mod test {
             "firefox.pdb",
             None,
             fixtures_dir().join("win64-ci"),
+            vec![],
+            std::env::temp_dir().join("dump_table_test_cache"),
         ));
         assert!(result.is_ok());
-        let result = result.unwrap();
+        let (_breakpad_id, result) = result.unwrap();
         assert_eq!(result.addr.len(), 1286);
         assert_eq!(result.addr[776], 0x31fc0);
         assert_eq!(